@@ -0,0 +1,518 @@
+use crate::common::*;
+use crate::route::*;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const BGP_VERSION: u8 = 4;
+
+const MSG_TYPE_OPEN: u8 = 1;
+const MSG_TYPE_UPDATE: u8 = 2;
+const MSG_TYPE_NOTIFICATION: u8 = 3;
+const MSG_TYPE_KEEPALIVE: u8 = 4;
+
+const OPT_PARAM_CAPABILITIES: u8 = 2;
+const CAP_MULTIPROTOCOL: u8 = 1;
+const CAP_FOUR_OCTET_ASN: u8 = 65;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_MP_REACH_NLRI: u8 = 14;
+const ATTR_MP_UNREACH_NLRI: u8 = 15;
+
+const AS_PATH_SEGMENT_AS_SET: u8 = 1;
+
+// RFC 4271 caps a BGP message at 4096 octets; reject anything claiming more up front
+// instead of trusting a peer-controlled length field to size an allocation and a read.
+const MAX_BGP_MESSAGE_LEN: usize = 4096;
+const BGP_HEADER_LEN: usize = 19;
+
+/// Maintains a live BGP session with a single collector or route-server peer, feeding
+/// announcements and withdrawals into the same `HashMap<AddrKey, HashSet<Route>>` table
+/// that `FindBottleneck::parse_mrt` builds from static MRT RIB dumps, so a bottleneck map
+/// can be produced from a live feed instead of a downloaded snapshot.
+pub(crate) struct BgpClient {
+    stream: TcpStream,
+    peer: SocketAddr,
+    hold_time: u16,
+}
+
+impl BgpClient {
+    /// Opens a TCP session to `peer`, sends an OPEN advertising the 4-octet-ASN capability
+    /// and the multiprotocol capability for both IPv4 and IPv6 unicast, and waits for the
+    /// peer's OPEN in response, negotiating the smaller of the two proposed hold timers.
+    pub(crate) fn connect(
+        peer: SocketAddr,
+        local_asn: u32,
+        router_id: std::net::Ipv4Addr,
+        hold_time: u16,
+    ) -> Result<Self> {
+        let stream = TcpStream::connect(peer).map_err(|io_error| Error::IoError {
+            io_error,
+            path: PathBuf::from(peer.to_string()),
+        })?;
+
+        let mut client = BgpClient {
+            stream,
+            peer,
+            hold_time,
+        };
+        client.send_open(local_asn, router_id)?;
+        client.hold_time = client.await_open()?;
+        Ok(client)
+    }
+
+    fn send_open(&mut self, local_asn: u32, router_id: std::net::Ipv4Addr) -> Result<()> {
+        let mut capabilities = Vec::new();
+        capabilities.extend_from_slice(&[CAP_FOUR_OCTET_ASN, 4]);
+        capabilities.extend_from_slice(&local_asn.to_be_bytes());
+        for afi in &[AFI_IPV4, AFI_IPV6] {
+            capabilities.extend_from_slice(&[CAP_MULTIPROTOCOL, 4]);
+            capabilities.extend_from_slice(&afi.to_be_bytes());
+            capabilities.push(0); // reserved
+            capabilities.push(SAFI_UNICAST);
+        }
+
+        let mut opt_params = vec![OPT_PARAM_CAPABILITIES, capabilities.len() as u8];
+        opt_params.extend_from_slice(&capabilities);
+
+        // Peers that only understand 2-octet ASNs should see AS_TRANS here; the real ASN
+        // is carried in the 4-octet-ASN capability above.
+        let as_trans: u16 = if local_asn > u32::from(u16::max_value()) {
+            23456
+        } else {
+            local_asn as u16
+        };
+
+        let mut body = vec![BGP_VERSION];
+        body.extend_from_slice(&as_trans.to_be_bytes());
+        body.extend_from_slice(&self.hold_time.to_be_bytes());
+        body.extend_from_slice(&router_id.octets());
+        body.push(opt_params.len() as u8);
+        body.extend_from_slice(&opt_params);
+
+        self.write_message(MSG_TYPE_OPEN, &body)
+    }
+
+    fn await_open(&mut self) -> Result<u16> {
+        loop {
+            let (msg_type, body) = self.read_message()?;
+            match msg_type {
+                MSG_TYPE_OPEN => {
+                    let peer_hold_time = Self::parse_open_hold_time(&body)?;
+                    self.write_message(MSG_TYPE_KEEPALIVE, &[])?;
+                    return Ok(self.hold_time.min(peer_hold_time));
+                }
+                MSG_TYPE_NOTIFICATION => {
+                    return Err(malformed(format!(
+                        "peer {} sent a NOTIFICATION before completing the OPEN handshake",
+                        self.peer
+                    )));
+                }
+                _ => {
+                    // Anything else before the peer's OPEN is unexpected; keep waiting.
+                }
+            }
+        }
+    }
+
+    /// OPEN body layout: Version(1) ASN(2) HoldTime(2) BGP Identifier(4) Opt Param Len(1)
+    /// Opt Params(var), matching the order `send_open` writes it in.
+    fn parse_open_hold_time(body: &[u8]) -> Result<u16> {
+        read_u16(body, 3)
+    }
+
+    fn write_message(&mut self, msg_type: u8, body: &[u8]) -> Result<()> {
+        Self::send_message(&mut self.stream, msg_type, body, &self.peer)
+    }
+
+    fn send_message(
+        stream: &mut TcpStream,
+        msg_type: u8,
+        body: &[u8],
+        peer: &SocketAddr,
+    ) -> Result<()> {
+        let length = BGP_HEADER_LEN + body.len();
+        let mut message = Vec::with_capacity(length);
+        message.extend_from_slice(&[0xFF; 16]); // marker: unauthenticated session
+        message.extend_from_slice(&(length as u16).to_be_bytes());
+        message.push(msg_type);
+        message.extend_from_slice(body);
+
+        stream.write_all(&message).map_err(|io_error| Error::IoError {
+            io_error,
+            path: PathBuf::from(peer.to_string()),
+        })
+    }
+
+    /// Reads one message header+body off the wire, rejecting a claimed length outside
+    /// `BGP_HEADER_LEN..=MAX_BGP_MESSAGE_LEN` before trusting it to size a read.
+    fn read_message(&mut self) -> Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; BGP_HEADER_LEN];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(|io_error| Error::IoError {
+                io_error,
+                path: PathBuf::from(self.peer.to_string()),
+            })?;
+
+        let length = u16::from_be_bytes([header[16], header[17]]) as usize;
+        let msg_type = header[18];
+
+        if length < BGP_HEADER_LEN || length > MAX_BGP_MESSAGE_LEN {
+            return Err(malformed(format!(
+                "peer {} sent a BGP message with invalid length {}",
+                self.peer, length
+            )));
+        }
+
+        let mut body = vec![0u8; length - BGP_HEADER_LEN];
+        self.stream
+            .read_exact(&mut body)
+            .map_err(|io_error| Error::IoError {
+                io_error,
+                path: PathBuf::from(self.peer.to_string()),
+            })?;
+
+        Ok((msg_type, body))
+    }
+
+    /// Reads UPDATE messages until the session ends, applying announcements and
+    /// withdrawals to `mrt_hm` as they arrive. A background thread answers KEEPALIVEs on
+    /// the negotiated hold timer so the session survives while this loop blocks on reads.
+    /// `find_as_bottleneck`/`write_bottleneck` are private to the `find_bottleneck`
+    /// module, so only code there can run them over `mrt_hm`, the way `locate` does for
+    /// `mrt_hm_unsorted`; this client does not run them itself.
+    pub(crate) fn run(&mut self, mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>) -> Result<()> {
+        let mut keepalive_stream = self.stream.try_clone().map_err(|io_error| Error::IoError {
+            io_error,
+            path: PathBuf::from(self.peer.to_string()),
+        })?;
+        let keepalive_peer = self.peer;
+        let interval = Duration::from_secs(u64::from((self.hold_time / 3).max(1)));
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if Self::send_message(&mut keepalive_stream, MSG_TYPE_KEEPALIVE, &[], &keepalive_peer).is_err() {
+                break;
+            }
+        });
+
+        loop {
+            let (msg_type, body) = self.read_message()?;
+            match msg_type {
+                MSG_TYPE_KEEPALIVE => continue,
+                MSG_TYPE_UPDATE => match Self::handle_update(&body, mrt_hm) {
+                    Ok(()) => {}
+                    Err(e) => info!("Dropping malformed UPDATE from {}: {:?}.", self.peer, e),
+                },
+                MSG_TYPE_NOTIFICATION => {
+                    info!("Peer {} sent NOTIFICATION, closing session.", self.peer);
+                    break;
+                }
+                other => info!("Ignoring unexpected BGP message type {} from {}.", other, self.peer),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_update(body: &[u8], mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>) -> Result<()> {
+        let mut pos = 0;
+        let withdrawn_len = read_u16(body, pos)? as usize;
+        pos += 2;
+        let withdrawn = Self::parse_nlri(take(body, pos, withdrawn_len)?, true)?;
+        pos += withdrawn_len;
+
+        let path_attr_len = read_u16(body, pos)? as usize;
+        pos += 2;
+        let attrs_end = pos
+            .checked_add(path_attr_len)
+            .filter(|end| *end <= body.len())
+            .ok_or_else(|| {
+                malformed(format!(
+                    "path attribute length {} exceeds the {} bytes remaining in the message",
+                    path_attr_len,
+                    body.len().saturating_sub(pos)
+                ))
+            })?;
+
+        let mut as_path: Option<Vec<u32>> = None;
+        let mut mp_reach = Vec::new();
+        let mut mp_unreach = Vec::new();
+
+        while pos < attrs_end {
+            let flags = read_u8(body, pos)?;
+            let type_code = read_u8(body, pos + 1)?;
+            let extended_length = flags & 0x10 != 0;
+            let (attr_len, header_len) = if extended_length {
+                (read_u16(body, pos + 2)? as usize, 4)
+            } else {
+                (read_u8(body, pos + 2)? as usize, 3)
+            };
+            let value = take(body, pos + header_len, attr_len)?;
+
+            match type_code {
+                ATTR_AS_PATH => as_path = Some(Self::parse_as_path(value)?),
+                ATTR_MP_REACH_NLRI => mp_reach = Self::parse_mp_reach(value)?,
+                ATTR_MP_UNREACH_NLRI => mp_unreach = Self::parse_mp_unreach(value)?,
+                _ => {}
+            }
+
+            pos += header_len + attr_len;
+        }
+
+        let nlri = Self::parse_nlri(take(body, attrs_end, body.len() - attrs_end)?, true)?;
+
+        // A withdrawal doesn't carry the previously announced AS_PATH, so drop the whole
+        // entry for the prefix rather than trying to pick out a single stale path.
+        for addr in withdrawn.into_iter().chain(mp_unreach.into_iter()) {
+            mrt_hm.remove(&AddrKey::from_address(&addr));
+        }
+
+        if let Some(mut as_path) = as_path {
+            // Matches match_rib_entry: AS-path prepending leaves consecutive duplicate
+            // ASNs that would otherwise eat into the bounded SUFFIX_LEN suffix.
+            as_path.dedup();
+            let route = Route::from_path(&as_path);
+            for addr in nlri.into_iter().chain(mp_reach.into_iter()) {
+                mrt_hm
+                    .entry(AddrKey::from_address(&addr))
+                    .or_insert_with(HashSet::new)
+                    .insert(route);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flattens AS_SEQUENCE segments in order; AS_SET segments are order-independent, so
+    /// their members are sorted before being appended to keep the resulting path stable.
+    fn parse_as_path(value: &[u8]) -> Result<Vec<u32>> {
+        let mut as_path = Vec::new();
+        let mut pos = 0;
+
+        while pos < value.len() {
+            let segment_type = read_u8(value, pos)?;
+            let segment_len = read_u8(value, pos + 1)? as usize;
+            pos += 2;
+
+            let mut asns = Vec::with_capacity(segment_len);
+            for i in 0..segment_len {
+                let asn_bytes = take(value, pos + i * 4, 4)?;
+                asns.push(u32::from_be_bytes([
+                    asn_bytes[0],
+                    asn_bytes[1],
+                    asn_bytes[2],
+                    asn_bytes[3],
+                ]));
+            }
+            pos += segment_len * 4;
+
+            if segment_type == AS_PATH_SEGMENT_AS_SET {
+                asns.sort_unstable();
+            }
+            as_path.extend(asns);
+        }
+
+        Ok(as_path)
+    }
+
+    /// Parses a withdrawn-routes or NLRI field: a sequence of `(prefix length, prefix
+    /// bytes)` entries, the bytes being the minimum number needed for the prefix length.
+    fn parse_nlri(bytes: &[u8], is_ipv4: bool) -> Result<Vec<Address>> {
+        let mut addrs = Vec::new();
+        let mut pos = 0;
+        let addr_len = if is_ipv4 { 4 } else { 16 };
+
+        while pos < bytes.len() {
+            let prefix_len = read_u8(bytes, pos)?;
+            if prefix_len as usize > addr_len * 8 {
+                return Err(malformed(format!(
+                    "prefix length {} exceeds the address width of {} bits",
+                    prefix_len,
+                    addr_len * 8
+                )));
+            }
+            pos += 1;
+            let byte_len = (prefix_len as usize + 7) / 8;
+
+            let prefix_bytes = take(bytes, pos, byte_len)?;
+            let mut octets = vec![0u8; addr_len];
+            octets[..byte_len].copy_from_slice(prefix_bytes);
+            pos += byte_len;
+
+            let ip = if is_ipv4 {
+                IpAddr::V4(std::net::Ipv4Addr::new(
+                    octets[0], octets[1], octets[2], octets[3],
+                ))
+            } else {
+                IpAddr::V6(std::net::Ipv6Addr::new(
+                    u16::from_be_bytes([octets[0], octets[1]]),
+                    u16::from_be_bytes([octets[2], octets[3]]),
+                    u16::from_be_bytes([octets[4], octets[5]]),
+                    u16::from_be_bytes([octets[6], octets[7]]),
+                    u16::from_be_bytes([octets[8], octets[9]]),
+                    u16::from_be_bytes([octets[10], octets[11]]),
+                    u16::from_be_bytes([octets[12], octets[13]]),
+                    u16::from_be_bytes([octets[14], octets[15]]),
+                ))
+            };
+
+            addrs.push(Address {
+                ip,
+                mask: prefix_len,
+            });
+        }
+
+        Ok(addrs)
+    }
+
+    /// MP_REACH_NLRI: AFI(2) SAFI(1) next-hop-length(1) next-hop(var) reserved(1) NLRI(var).
+    fn parse_mp_reach(value: &[u8]) -> Result<Vec<Address>> {
+        let afi = read_u16(value, 0)?;
+        let nexthop_len = read_u8(value, 3)? as usize;
+        let nlri_start = 4 + nexthop_len + 1;
+        let nlri = take(value, nlri_start, value.len().saturating_sub(nlri_start))?;
+        Self::parse_nlri(nlri, afi == AFI_IPV4)
+    }
+
+    /// MP_UNREACH_NLRI: AFI(2) SAFI(1) withdrawn NLRI(var).
+    fn parse_mp_unreach(value: &[u8]) -> Result<Vec<Address>> {
+        let afi = read_u16(value, 0)?;
+        let nlri = take(value, 3, value.len().saturating_sub(3))?;
+        Self::parse_nlri(nlri, afi == AFI_IPV4)
+    }
+}
+
+/// Builds the `Error` returned when a peer's message can't be accepted (a truncated or
+/// out-of-range field, or a NOTIFICATION during the OPEN handshake), rather than a local
+/// I/O failure.
+fn malformed(reason: String) -> Error {
+    Error::IoError {
+        io_error: io::Error::new(io::ErrorKind::InvalidData, reason),
+        path: PathBuf::from("<bgp message>"),
+    }
+}
+
+/// Returns `bytes[pos..pos + len]`, or an `Error` if that range runs past the end of
+/// `bytes` instead of panicking the session on a truncated or hostile message.
+fn take(bytes: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    bytes.get(pos..pos + len).ok_or_else(|| {
+        malformed(format!(
+            "expected {} bytes at offset {} but only {} remain",
+            len,
+            pos,
+            bytes.len().saturating_sub(pos.min(bytes.len()))
+        ))
+    })
+}
+
+fn read_u8(bytes: &[u8], pos: usize) -> Result<u8> {
+    bytes
+        .get(pos)
+        .copied()
+        .ok_or_else(|| malformed(format!("expected a byte at offset {}", pos)))
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Result<u16> {
+    let slice = take(bytes, pos, 2)?;
+    Ok(u16::from_be_bytes([slice[0], slice[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_valid_ipv4_nlri() -> Result<(), Error> {
+        // /24 prefix 1.0.139.0 encoded as (length, 3 address octets).
+        let bytes = [24u8, 1, 0, 139];
+        let addrs = BgpClient::parse_nlri(&bytes, true)?;
+
+        assert_eq!(addrs, vec![Address::from_str("1.0.139.0/24")?]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_an_nlri_prefix_length_wider_than_the_address() {
+        // A prefix length of 252 bits can't fit in a 32-bit IPv4 address.
+        let bytes = [252u8, 1, 0, 139];
+        assert!(BgpClient::parse_nlri(&bytes, true).is_err());
+    }
+
+    #[test]
+    fn rejects_an_nlri_truncated_mid_prefix() {
+        // Declares a /24 (needs 3 address octets) but only one byte follows.
+        let bytes = [24u8, 1];
+        assert!(BgpClient::parse_nlri(&bytes, true).is_err());
+    }
+
+    #[test]
+    fn parses_a_valid_as_sequence() -> Result<(), Error> {
+        let mut bytes = vec![2, 2]; // AS_SEQUENCE, 2 ASNs
+        bytes.extend_from_slice(&100u32.to_be_bytes());
+        bytes.extend_from_slice(&200u32.to_be_bytes());
+
+        assert_eq!(BgpClient::parse_as_path(&bytes)?, vec![100, 200]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_an_as_path_segment_truncated_mid_asn() {
+        let mut bytes = vec![2, 2]; // claims 2 ASNs
+        bytes.extend_from_slice(&100u32.to_be_bytes()); // only one is present
+
+        assert!(BgpClient::parse_as_path(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_an_update_with_a_withdrawn_length_past_the_buffer() {
+        // withdrawn_len claims 50 bytes but the message body has none.
+        let body = 50u16.to_be_bytes().to_vec();
+        let mut mrt_hm = HashMap::new();
+
+        assert!(BgpClient::handle_update(&body, &mut mrt_hm).is_err());
+    }
+
+    #[test]
+    fn rejects_an_update_with_a_path_attribute_length_past_the_buffer() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u16.to_be_bytes()); // no withdrawn routes
+        body.extend_from_slice(&40u16.to_be_bytes()); // path_attr_len, way past the buffer
+        let mut mrt_hm = HashMap::new();
+
+        assert!(BgpClient::handle_update(&body, &mut mrt_hm).is_err());
+    }
+
+    #[test]
+    fn parses_peer_hold_time_from_an_open_body() -> Result<(), Error> {
+        let mut body = vec![BGP_VERSION];
+        body.extend_from_slice(&64512u16.to_be_bytes()); // ASN (as_trans)
+        body.extend_from_slice(&180u16.to_be_bytes()); // hold time
+        body.extend_from_slice(&[0u8; 4]); // BGP identifier
+        body.push(0); // opt param len, no opt params
+
+        assert_eq!(BgpClient::parse_open_hold_time(&body)?, 180);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_mp_reach_nlri_with_an_ipv6_nexthop() -> Result<(), Error> {
+        let mut value = Vec::new();
+        value.extend_from_slice(&AFI_IPV6.to_be_bytes());
+        value.push(SAFI_UNICAST);
+        value.push(16); // next hop length
+        value.extend_from_slice(&[0u8; 16]); // next hop
+        value.push(0); // reserved
+        value.push(32); // /32 prefix length
+        value.extend_from_slice(&[0x20, 0x01, 0x03, 0x18]); // 2001:318::/32
+
+        let addrs = BgpClient::parse_mp_reach(&value)?;
+        assert_eq!(addrs, vec![Address::from_str("2001:318::/32")?]);
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::common::*;
+use crate::route::*;
 
 /// Contains the mapping of each prefix to its bottleneck asn.
 #[derive(Debug, PartialEq)]
@@ -7,6 +8,15 @@ pub(crate) struct FindBottleneck {
 }
 
 impl FindBottleneck {
+    /// Creates an empty `FindBottleneck`, e.g. for a caller driving `BgpClient::run`
+    /// against a live feed to batch its table through `find_as_bottleneck`/
+    /// `write_bottleneck` on demand or on a timer, the same way `locate` does for each
+    /// static MRT chunk.
+    pub(crate) fn new() -> Self {
+        FindBottleneck {
+            prefix_asn: HashMap::new(),
+        }
+    }
 
     fn open_files(dir: &PathBuf) -> Result<Vec<GzDecoder<BufReader<File>>>> {
         let mut file_decoders = Vec::new();
@@ -54,7 +64,7 @@ impl FindBottleneck {
 
         // First collect all the files which does not preserve order of records by prefix.
         // Use them every time we are processing batch.
-        let mut mrt_hm_unsorted = HashMap::new();
+        let mut mrt_hm_unsorted: HashMap<AddrKey, HashSet<Route>> = HashMap::new();
         for i in 0..file_decoders_unsorted.len() {
             // Load all data at once without batching.
             Self::parse_mrt(&mut file_decoders_unsorted[i], &mut mrt_hm_unsorted, u8::max_value(), &mut HashMap::new())?;
@@ -63,7 +73,7 @@ impl FindBottleneck {
         // Used to keep track of the last element of the batch.
         // Since a prefix may have multiple records in a file,
         // without this workaround it may be either not written at all or written twice.
-        let mut next_mrt_hm = HashMap::new();
+        let mut next_mrt_hm: HashMap<AddrKey, HashSet<Route>> = HashMap::new();
         let step: u8 = 1 << 4; // should be a power of 2
         for current_start_high_octet in (0..u8::max_value()).step_by(step as usize) {
             let current_end_high_octet: u8 = current_start_high_octet.saturating_add(step);
@@ -85,26 +95,22 @@ impl FindBottleneck {
             for (prefix, paths_from_sorted) in &mut mrt_hm {
                 match mrt_hm_unsorted.get(prefix) {
                     Some(paths_from_unsorted) => {
-                        for path in paths_from_unsorted {
-                            paths_from_sorted.insert(path.to_vec());
+                        for route in paths_from_unsorted {
+                            paths_from_sorted.insert(*route);
                         }
                         mrt_hm_unsorted.remove(&prefix);
                     }
                     None => continue
-                }           
+                }
             }
 
-            let mut bottleneck = FindBottleneck {
-                prefix_asn: HashMap::new(),
-            };
+            let mut bottleneck = Self::new();
             bottleneck.find_as_bottleneck(&mut mrt_hm)?;
             bottleneck.write_bottleneck(out)?;
         }
 
         // Write the remaining values from unsorted files.
-        let mut bottleneck = FindBottleneck {
-            prefix_asn: HashMap::new(),
-        };
+        let mut bottleneck = Self::new();
         bottleneck.find_as_bottleneck(&mut mrt_hm_unsorted)?;
         bottleneck.write_bottleneck(out)?;
 
@@ -116,21 +122,21 @@ impl FindBottleneck {
     /// the common asns to be the bottleneck.
     fn find_as_bottleneck(
         &mut self,
-        mrt_hm: &mut HashMap<Address, HashSet<Vec<u32>>>,
+        mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>,
     ) -> Result<(), Error> {
-        // In the vector value, the first element is the final AS (so the actual AS of the IP,
-        // not some AS on the path). The last element is the critical AS on the path that
-        // determines the bottleneck.
-        let mut prefix_to_common_suffix: HashMap<Address, Vec<u32>> = HashMap::new();
+        // In the common suffix, the last element is the final AS (so the actual AS of the
+        // IP, not some AS on the path). The first element is the critical AS on the path
+        // that determines the bottleneck.
+        let mut prefix_to_common_suffix: HashMap<AddrKey, Route> = HashMap::new();
 
         Self::find_common_suffix(mrt_hm, &mut prefix_to_common_suffix)?;
 
-        for (addr, mut as_path) in prefix_to_common_suffix {
-            let asn = match as_path.pop() {
-                Some(a) => a,
+        for (addr, route) in prefix_to_common_suffix {
+            let asn = match route.suffix().first() {
+                Some(a) => *a,
                 None => panic!("ERROR: No ASN"), // TODO: Handle error
             };
-            self.prefix_asn.insert(addr, asn);
+            self.prefix_asn.insert(addr.to_address(), asn);
         }
 
         Ok(())
@@ -139,43 +145,53 @@ impl FindBottleneck {
     /// Logic that finds the mapping of each prefix and the asns common to all of the prefix's asn
     /// paths.
     fn find_common_suffix(
-        mrt_hm: &mut HashMap<Address, HashSet<Vec<u32>>>,
-        prefix_to_common_suffix: &mut HashMap<Address, Vec<u32>>,
+        mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>,
+        prefix_to_common_suffix: &mut HashMap<AddrKey, Route>,
     ) -> Result<(), Error> {
-        'outer: for (prefix, as_paths) in mrt_hm.iter() {
-            let mut as_paths_sorted: Vec<&Vec<u32>> = as_paths.iter().collect();
+        'outer: for (prefix, routes) in mrt_hm.iter() {
+            let mut routes_sorted: Vec<&Route> = routes.iter().collect();
 
-            as_paths_sorted.sort_by(|a, b| a.len().cmp(&b.len())); // descending
+            routes_sorted.sort_by_key(|route| route.suffix().len());
 
-            let mut rev_common_suffix: Vec<u32> = as_paths_sorted[0].to_vec();
-            rev_common_suffix.reverse();
+            let mut common_suffix: Vec<u32> = routes_sorted[0].suffix().to_vec();
 
-            for as_path in as_paths_sorted.iter().skip(1) {
-                // first one is already in rev_common_suffix
-                let mut rev_as_path: Vec<u32> = as_path.to_vec();
-                rev_as_path.reverse();
+            for route in routes_sorted.iter().skip(1) {
+                let suffix = route.suffix();
 
                 // Every IP should always belong to only one AS
-                if rev_common_suffix.first() != rev_as_path.first() {
+                if common_suffix.last() != suffix.last() {
                     warn!(
                             "Every IP should belong to one AS. Prefix: `{:?}` has anomalous AS paths: `{:?}`.",
-                            &prefix, &as_paths
+                            &prefix, &routes
                         );
                     continue 'outer;
                 }
 
-                // first element is already checked
-                for i in 1..rev_common_suffix.len() {
-                    if rev_as_path[i] != rev_common_suffix[i] {
-                        rev_common_suffix.truncate(i);
-                        break;
-                    }
+                // Walk inward from the origin (the end of each slice); the last elements
+                // are already known to match above.
+                let overlap = common_suffix.len().min(suffix.len());
+                let mut matched = 1;
+                while matched < overlap
+                    && common_suffix[common_suffix.len() - 1 - matched]
+                        == suffix[suffix.len() - 1 - matched]
+                {
+                    matched += 1;
+                }
+
+                if matched == SUFFIX_LEN && common_suffix.len() == SUFFIX_LEN && suffix.len() == SUFFIX_LEN {
+                    warn!(
+                        "Prefix `{:?}` has AS paths that agree across the whole stored suffix length ({}); truncated paths may hide a longer common suffix, so the bottleneck could be ambiguous.",
+                        &prefix, SUFFIX_LEN
+                    );
                 }
+
+                let new_len = common_suffix.len();
+                common_suffix = common_suffix[new_len - matched..].to_vec();
             }
-            // rev_common_suffix.reverse();
+
             prefix_to_common_suffix
                 .entry(*prefix)
-                .or_insert(rev_common_suffix);
+                .or_insert_with(|| Route::from_path(&common_suffix));
         }
 
         Ok(())
@@ -185,9 +201,9 @@ impl FindBottleneck {
     /// containing the prefix and associated as paths.
     fn parse_mrt(
         reader: &mut dyn Read,
-        mrt_hm: &mut HashMap<Address, HashSet<Vec<u32>>>,
+        mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>,
         current_end_high_octet: u8,
-        next_mrt_hm: &mut HashMap<Address, HashSet<Vec<u32>>>
+        next_mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>
     ) -> Result<()> {
         let mut reader = Reader { stream: reader };
         loop {
@@ -264,9 +280,9 @@ impl FindBottleneck {
         entries: Vec<mrt_rs::records::tabledump::RIBEntry>,
         ip: IpAddr,
         mask: u8,
-        mrt_hm: &mut HashMap<Address, HashSet<Vec<u32>>>,
+        mrt_hm: &mut HashMap<AddrKey, HashSet<Route>>,
     ) -> Result<()> {
-        let addr = Address { ip, mask };
+        let addr = AddrKey::from_address(&Address { ip, mask });
 
         for rib_entry in entries {
             match AsPathParser::parse(&rib_entry.attributes) {
@@ -275,7 +291,7 @@ impl FindBottleneck {
                     mrt_hm
                         .entry(addr)
                         .or_insert_with(HashSet::new)
-                        .insert(as_path);
+                        .insert(Route::from_path(&as_path));
                 }
                 Err(e) => info!("ERROR: {:?}. ", e), // TODO: Handle error
             };
@@ -320,69 +336,84 @@ impl FindBottleneck {
 
         Ok(())
     }
+
+    /// Consumes `self` and hands back the prefix-to-ASN map, e.g. for `PrefixTrie` to
+    /// build a longest-prefix-match index without cloning it.
+    pub(crate) fn into_prefix_map(self) -> HashMap<Address, u32> {
+        self.prefix_asn
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn setup_mrt_hm() -> Result<HashMap<Address, HashSet<Vec<u32>>>, Error> {
-        let mut mrt_hm: HashMap<Address, HashSet<Vec<u32>>> = HashMap::new();
+    fn setup_mrt_hm() -> Result<HashMap<AddrKey, HashSet<Route>>, Error> {
+        let mut mrt_hm: HashMap<AddrKey, HashSet<Route>> = HashMap::new();
         let ip_str = "1.0.139.0";
-        let addr = Address {
+        let addr = AddrKey::from_address(&Address {
             ip: IpAddr::from_str(ip_str).map_err(|addr_parse| Error::AddrParse {
                 addr_parse,
                 bad_addr: ip_str.to_string(),
             })?,
             mask: 24,
-        };
+        });
 
-        let mut asn_paths = HashSet::new();
-        asn_paths.insert(vec![2497, 38040, 23969]);
-        asn_paths.insert(vec![25152, 6939, 4766, 38040, 23969]);
-        asn_paths.insert(vec![4777, 6939, 4766, 38040, 23969]);
-        mrt_hm.insert(addr, asn_paths);
+        let mut routes = HashSet::new();
+        routes.insert(Route::from_path(&[2497, 38040, 23969]));
+        routes.insert(Route::from_path(&[25152, 6939, 4766, 38040, 23969]));
+        routes.insert(Route::from_path(&[4777, 6939, 4766, 38040, 23969]));
+        mrt_hm.insert(addr, routes);
 
         let ip_str = "1.0.204.0";
-        let addr = Address {
+        let addr = AddrKey::from_address(&Address {
             ip: IpAddr::from_str(ip_str).map_err(|addr_parse| Error::AddrParse {
                 addr_parse,
                 bad_addr: ip_str.to_string(),
             })?,
             mask: 22,
-        };
-        let mut asn_paths = HashSet::new();
-        asn_paths.insert(vec![2497, 38040, 23969]);
-        asn_paths.insert(vec![4777, 6939, 4766, 38040, 23969]);
-        asn_paths.insert(vec![25152, 2914, 38040, 23969]);
-        mrt_hm.insert(addr, asn_paths);
+        });
+        let mut routes = HashSet::new();
+        routes.insert(Route::from_path(&[2497, 38040, 23969]));
+        routes.insert(Route::from_path(&[4777, 6939, 4766, 38040, 23969]));
+        routes.insert(Route::from_path(&[25152, 2914, 38040, 23969]));
+        mrt_hm.insert(addr, routes);
 
         let ip_str = "1.0.6.0";
-        let addr = Address {
+        let addr = AddrKey::from_address(&Address {
             ip: IpAddr::from_str(ip_str).map_err(|addr_parse| Error::AddrParse {
                 addr_parse,
                 bad_addr: ip_str.to_string(),
             })?,
             mask: 24,
-        };
-        let mut asn_paths = HashSet::new();
-        asn_paths.insert(vec![2497, 4826, 38803, 56203]);
-        asn_paths.insert(vec![25152, 6939, 4826, 38803, 56203]);
-        asn_paths.insert(vec![4777, 6939, 4826, 38803, 56203]);
-        mrt_hm.insert(addr, asn_paths);
+        });
+        let mut routes = HashSet::new();
+        routes.insert(Route::from_path(&[2497, 4826, 38803, 56203]));
+        routes.insert(Route::from_path(&[25152, 6939, 4826, 38803, 56203]));
+        routes.insert(Route::from_path(&[4777, 6939, 4826, 38803, 56203]));
+        mrt_hm.insert(addr, routes);
 
         Ok(mrt_hm)
     }
 
     #[test]
     fn finds_common_suffix_from_mrt_hashmap() -> Result<(), Error> {
-        let mut want: HashMap<Address, Vec<u32>> = HashMap::new();
-        want.insert(Address::from_str("1.0.139.0/24")?, vec![23969, 38040]);
-        want.insert(Address::from_str("1.0.204.0/22")?, vec![23969, 38040]);
-        want.insert(Address::from_str("1.0.6.0/24")?, vec![56203, 38803, 4826]);
+        let mut want: HashMap<AddrKey, Route> = HashMap::new();
+        want.insert(
+            AddrKey::from_address(&Address::from_str("1.0.139.0/24")?),
+            Route::from_path(&[38040, 23969]),
+        );
+        want.insert(
+            AddrKey::from_address(&Address::from_str("1.0.204.0/22")?),
+            Route::from_path(&[38040, 23969]),
+        );
+        want.insert(
+            AddrKey::from_address(&Address::from_str("1.0.6.0/24")?),
+            Route::from_path(&[4826, 38803, 56203]),
+        );
 
         let mut mrt_hm = setup_mrt_hm()?;
-        let mut have: HashMap<Address, Vec<u32>> = HashMap::new();
+        let mut have: HashMap<AddrKey, Route> = HashMap::new();
 
         assert_eq!(
             FindBottleneck::find_common_suffix(&mut mrt_hm, &mut have)?,
@@ -416,6 +447,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn warns_and_keeps_the_full_suffix_when_paths_agree_across_it() -> Result<(), Error> {
+        // Both paths are longer than SUFFIX_LEN and share the same trailing 6 ASNs, but
+        // differ further up the path than either Route kept, so the full stored suffix
+        // agrees and the common-suffix computation can't tell if a longer true common
+        // suffix exists. This should still succeed (just with a warning), returning the
+        // whole stored suffix as the common one.
+        let tail = [2497, 4826, 38803, 56203, 111213, 99999];
+        let mut mrt_hm: HashMap<AddrKey, HashSet<Route>> = HashMap::new();
+        let addr = AddrKey::from_address(&Address::from_str("1.0.139.0/24")?);
+
+        let mut routes = HashSet::new();
+        routes.insert(Route::from_path(
+            &[&[555][..], &tail[..]].concat(),
+        ));
+        routes.insert(Route::from_path(
+            &[&[666, 777][..], &tail[..]].concat(),
+        ));
+        mrt_hm.insert(addr, routes);
+
+        let mut have: HashMap<AddrKey, Route> = HashMap::new();
+        FindBottleneck::find_common_suffix(&mut mrt_hm, &mut have)?;
+
+        let mut want = HashMap::new();
+        want.insert(addr, Route::from_path(&tail));
+        assert_eq!(have, want);
+
+        Ok(())
+    }
+
     #[test]
     fn ipaddr_from_ipv6_short() -> Result<(), Error> {
         let have = FindBottleneck::format_ip(&[32, 1, 3, 24], false)?;
@@ -0,0 +1,280 @@
+use crate::common::*;
+use crate::find_bottleneck::FindBottleneck;
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    asn: Option<u32>,
+    children: [Option<Box<TrieNode>>; 2],
+}
+
+impl TrieNode {
+    fn new() -> Self {
+        TrieNode {
+            asn: None,
+            children: [None, None],
+        }
+    }
+}
+
+/// Binary trie over prefix bits (one node per bit, no path compression), answering longest-prefix-match queries
+/// against the bottleneck map built by `FindBottleneck::locate`. IPv4 and IPv6 prefixes
+/// live in separate roots since their addresses have unrelated bit widths.
+pub(crate) struct PrefixTrie {
+    v4_root: TrieNode,
+    v6_root: TrieNode,
+}
+
+impl PrefixTrie {
+    /// Consumes a `FindBottleneck` and builds a trie from every `(prefix, asn)` pair it
+    /// produced, so the flat `write_bottleneck` output isn't the only way to query it.
+    pub(crate) fn from_bottleneck(bottleneck: FindBottleneck) -> Self {
+        let mut trie = PrefixTrie {
+            v4_root: TrieNode::new(),
+            v6_root: TrieNode::new(),
+        };
+
+        for (prefix, asn) in bottleneck.into_prefix_map() {
+            trie.insert(prefix, asn);
+        }
+
+        trie
+    }
+
+    /// Walks `mask` bits of `prefix`, creating nodes as needed, and stores `asn` at the
+    /// node reached at that depth.
+    fn insert(&mut self, prefix: Address, asn: u32) {
+        let root = match prefix.ip {
+            IpAddr::V4(_) => &mut self.v4_root,
+            IpAddr::V6(_) => &mut self.v6_root,
+        };
+
+        let mut node = root;
+        for bit in Self::address_bits(prefix.ip).into_iter().take(prefix.mask as usize) {
+            node = node.children[bit as usize].get_or_insert_with(|| Box::new(TrieNode::new()));
+        }
+        node.asn = Some(asn);
+    }
+
+    /// Returns the ASN of the most specific prefix covering `ip`: the `asn` carried by the
+    /// deepest node visited while walking `ip`'s bits, or `None` if no stored prefix covers
+    /// it at all. Callers can use a `None` result to report IPs with no covering prefix.
+    pub(crate) fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        let root = match ip {
+            IpAddr::V4(_) => &self.v4_root,
+            IpAddr::V6(_) => &self.v6_root,
+        };
+
+        let mut node = root;
+        let mut best = node.asn;
+
+        for bit in Self::address_bits(ip) {
+            match &node.children[bit as usize] {
+                Some(child) => {
+                    node = child;
+                    if node.asn.is_some() {
+                        best = node.asn;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        best
+    }
+
+    /// The address octets of `ip`, expanded into individual bits, most significant first.
+    fn address_bits(ip: IpAddr) -> Vec<u8> {
+        let octets: Vec<u8> = match ip {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+
+        octets
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| (byte >> (7 - i)) & 1))
+            .collect()
+    }
+
+    /// Serializes this trie depth-first into a compact varint-encoded byte stream. Each
+    /// node writes a bitmask of which children and ASN are present, followed by the ASN
+    /// itself when present, then recurses into its children; shared path prefixes are
+    /// written once by the trie structure instead of once per line the way
+    /// `write_bottleneck` does.
+    pub(crate) fn write_binary(&self, out: &mut dyn Write) -> Result<()> {
+        Self::write_node(&self.v4_root, out)?;
+        Self::write_node(&self.v6_root, out)
+    }
+
+    fn write_node(node: &TrieNode, out: &mut dyn Write) -> Result<()> {
+        let mut flags: u32 = 0;
+        if node.asn.is_some() {
+            flags |= NODE_HAS_ASN;
+        }
+        if node.children[0].is_some() {
+            flags |= NODE_HAS_LEFT;
+        }
+        if node.children[1].is_some() {
+            flags |= NODE_HAS_RIGHT;
+        }
+
+        write_varint(out, flags)?;
+        if let Some(asn) = node.asn {
+            write_varint(out, asn)?;
+        }
+        if let Some(child) = &node.children[0] {
+            Self::write_node(child, out)?;
+        }
+        if let Some(child) = &node.children[1] {
+            Self::write_node(child, out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds a trie from the byte stream written by `write_binary`.
+    pub(crate) fn read_binary(r: &mut dyn Read) -> Result<Self> {
+        Ok(PrefixTrie {
+            v4_root: Self::read_node(r)?,
+            v6_root: Self::read_node(r)?,
+        })
+    }
+
+    fn read_node(r: &mut dyn Read) -> Result<TrieNode> {
+        let flags = read_varint(r)?;
+        let mut node = TrieNode::new();
+
+        if flags & NODE_HAS_ASN != 0 {
+            node.asn = Some(read_varint(r)?);
+        }
+        if flags & NODE_HAS_LEFT != 0 {
+            node.children[0] = Some(Box::new(Self::read_node(r)?));
+        }
+        if flags & NODE_HAS_RIGHT != 0 {
+            node.children[1] = Some(Box::new(Self::read_node(r)?));
+        }
+
+        Ok(node)
+    }
+}
+
+const NODE_HAS_ASN: u32 = 1;
+const NODE_HAS_LEFT: u32 = 2;
+const NODE_HAS_RIGHT: u32 = 4;
+
+/// Writes `value` as an unsigned LEB128 varint: little-endian base-128 groups, high bit
+/// set on every byte but the last. ASNs need up to five bytes to cover the full 32-bit
+/// space.
+fn write_varint(out: &mut dyn Write, mut value: u32) -> Result<()> {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        out.write_all(&[byte]).map_err(|io_error| Error::IoError {
+            io_error,
+            path: PathBuf::from("<bottleneck binary stream>"),
+        })?;
+        if value == 0 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// ASNs need up to 5 bytes to cover the full 32-bit space, so a 6th continuation byte
+// can only come from a corrupted or hostile stream; reading it would shift `value` by
+// 35 bits and overflow.
+const MAX_VARINT_BYTES: u32 = 5;
+
+/// Reads a varint written by `write_varint`. Rejects a stream that never terminates its
+/// continuation bytes within `MAX_VARINT_BYTES`, instead of shifting `value` out of range.
+fn read_varint(r: &mut dyn Read) -> Result<u32> {
+    let mut value: u32 = 0;
+    let mut shift = 0;
+
+    for _ in 0..MAX_VARINT_BYTES {
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte).map_err(|io_error| Error::IoError {
+            io_error,
+            path: PathBuf::from("<bottleneck binary stream>"),
+        })?;
+
+        value |= u32::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+
+    Err(Error::IoError {
+        io_error: io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("varint did not terminate within {} bytes", MAX_VARINT_BYTES),
+        ),
+        path: PathBuf::from("<bottleneck binary stream>"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_trie() -> Result<PrefixTrie, Error> {
+        let mut trie = PrefixTrie {
+            v4_root: TrieNode::new(),
+            v6_root: TrieNode::new(),
+        };
+        trie.insert(Address::from_str("1.0.0.0/8")?, 38040);
+        trie.insert(Address::from_str("1.0.139.0/24")?, 23969);
+        trie.insert(Address::from_str("2001:318::/32")?, 2497);
+
+        Ok(trie)
+    }
+
+    #[test]
+    fn lookup_returns_most_specific_covering_prefix() -> Result<(), Error> {
+        let trie = setup_trie()?;
+
+        assert_eq!(trie.lookup(IpAddr::from_str("1.0.139.5").unwrap()), Some(23969));
+        assert_eq!(trie.lookup(IpAddr::from_str("1.0.1.1").unwrap()), Some(38040));
+        assert_eq!(trie.lookup(IpAddr::from_str("2.0.0.1").unwrap()), None);
+        assert_eq!(trie.lookup(IpAddr::from_str("2001:318::1").unwrap()), Some(2497));
+
+        Ok(())
+    }
+
+    #[test]
+    fn binary_round_trip_preserves_lookups() -> Result<(), Error> {
+        let trie = setup_trie()?;
+
+        let mut bytes = Vec::new();
+        trie.write_binary(&mut bytes)?;
+
+        let restored = PrefixTrie::read_binary(&mut bytes.as_slice())?;
+
+        assert_eq!(restored.lookup(IpAddr::from_str("1.0.139.5").unwrap()), Some(23969));
+        assert_eq!(restored.lookup(IpAddr::from_str("1.0.1.1").unwrap()), Some(38040));
+        assert_eq!(restored.lookup(IpAddr::from_str("2.0.0.1").unwrap()), None);
+        assert_eq!(restored.lookup(IpAddr::from_str("2001:318::1").unwrap()), Some(2497));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_varint_round_trips_a_five_byte_value() -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, u32::max_value())?;
+
+        assert_eq!(read_varint(&mut bytes.as_slice())?, u32::max_value());
+        Ok(())
+    }
+
+    #[test]
+    fn read_varint_rejects_a_sixth_continuation_byte() {
+        let bytes = [0x80u8, 0x80, 0x80, 0x80, 0x80, 0x01];
+
+        assert!(read_varint(&mut bytes.as_slice()).is_err());
+    }
+}
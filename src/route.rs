@@ -0,0 +1,126 @@
+use crate::common::*;
+
+/// Number of ASNs kept from the tail (origin end) of each AS path. `find_common_suffix`
+/// only ever inspects this tail, so on a full global RIB storing whole paths wastes most
+/// of the memory an AS-path table costs.
+pub(crate) const SUFFIX_LEN: usize = 6;
+
+/// A single AS path, compacted to its last `SUFFIX_LEN` hops in original (origin-last)
+/// order: `suffix().last()` is always the origin ASN, the same convention the raw
+/// `Vec<u32>` paths used before. Paths shorter than `SUFFIX_LEN` record their true
+/// `path_len` so the common-suffix computation still stops at the shortest path instead
+/// of reading stale zeroes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Route {
+    path_suffix: [u32; SUFFIX_LEN],
+    path_len: u32,
+}
+
+impl Route {
+    /// Builds a `Route` from a full, origin-last AS path, keeping only the trailing
+    /// `SUFFIX_LEN` ASNs. Truncating a single path here is routine on a full global RIB,
+    /// where AS paths well over `SUFFIX_LEN` hops are common; it's only a problem if
+    /// multiple truncated paths for the same prefix still agree across the whole stored
+    /// suffix, which `find_common_suffix` detects and warns about on its own.
+    pub(crate) fn from_path(path: &[u32]) -> Self {
+        let path_len = path.len();
+        let take = path_len.min(SUFFIX_LEN);
+
+        let mut path_suffix = [0u32; SUFFIX_LEN];
+        path_suffix[..take].copy_from_slice(&path[path_len - take..]);
+
+        Route {
+            path_suffix,
+            path_len: path_len as u32,
+        }
+    }
+
+    /// The ASNs actually populated in `path_suffix`: the last `min(path_len, SUFFIX_LEN)`
+    /// hops of the original path, origin-last, i.e. `suffix().last()` is the origin ASN.
+    pub(crate) fn suffix(&self) -> &[u32] {
+        &self.path_suffix[..(self.path_len as usize).min(SUFFIX_LEN)]
+    }
+}
+
+/// IPv4 prefix key: 4 address octets plus the prefix length. Every field already has
+/// alignment 1, so this is 5 bytes with the default representation; there's no padding
+/// for `#[repr(packed)]` to remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct V4Addr {
+    addr: [u8; 4],
+    pfxlen: u8,
+}
+
+/// IPv6 prefix key: 16 address octets plus the prefix length. Same reasoning as
+/// `V4Addr`: already 17 bytes with no padding to remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct V6Addr {
+    addr: [u8; 16],
+    pfxlen: u8,
+}
+
+/// Map key for the per-prefix route table. The `V4Addr` and `V6Addr` payloads are 5 and
+/// 17 bytes, but the enum is sized for its larger variant plus a tag, so every `AddrKey`
+/// takes 18 bytes whichever address family it holds; no layout here is packed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum AddrKey {
+    V4(V4Addr),
+    V6(V6Addr),
+}
+
+impl AddrKey {
+    pub(crate) fn from_address(addr: &Address) -> Self {
+        match addr.ip {
+            IpAddr::V4(ipv4) => AddrKey::V4(V4Addr {
+                addr: ipv4.octets(),
+                pfxlen: addr.mask,
+            }),
+            IpAddr::V6(ipv6) => AddrKey::V6(V6Addr {
+                addr: ipv6.octets(),
+                pfxlen: addr.mask,
+            }),
+        }
+    }
+
+    pub(crate) fn to_address(self) -> Address {
+        match self {
+            AddrKey::V4(v4) => Address {
+                ip: IpAddr::V4(std::net::Ipv4Addr::from(v4.addr)),
+                mask: v4.pfxlen,
+            },
+            AddrKey::V6(v6) => Address {
+                ip: IpAddr::V6(std::net::Ipv6Addr::from(v6.addr)),
+                mask: v6.pfxlen,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_path_keeps_the_whole_path_when_it_fits() {
+        let route = Route::from_path(&[2497, 38040, 23969]);
+
+        assert_eq!(route.suffix(), &[2497, 38040, 23969]);
+    }
+
+    #[test]
+    fn from_path_truncates_a_path_longer_than_suffix_len() {
+        // 8 hops, origin-last; only the trailing SUFFIX_LEN (6) should survive.
+        let path = [701, 1299, 3356, 2497, 4826, 38803, 56203, 99999];
+        let route = Route::from_path(&path);
+
+        assert_eq!(route.suffix(), &path[path.len() - SUFFIX_LEN..]);
+    }
+
+    #[test]
+    fn addr_key_round_trips_through_address() -> Result<(), Error> {
+        let addr = Address::from_str("1.0.139.0/24")?;
+
+        assert_eq!(AddrKey::from_address(&addr).to_address(), addr);
+        Ok(())
+    }
+}